@@ -1,12 +1,20 @@
 use anyhow::{anyhow, bail, Context, Result};
+use rand::Rng;
+use std::collections::HashMap;
 use std::ffi::{OsStr, OsString};
-use std::fs::{self, File};
-use std::io::{BufReader, BufWriter, Write};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Read, Write};
 use std::os::linux::fs::MetadataExt;
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::os::unix::io::AsRawFd;
 use std::os::unix::prelude::CommandExt;
 use std::path::{Path, PathBuf};
 use std::process::Command;
 
+use nix::mount::MsFlags;
+use nix::sched::{setns, CloneFlags};
+use nix::sys::wait::WaitStatus;
+
 use crate::container::Container;
 use crate::distrod_config::{self, DistrodConfig};
 use crate::envfile::EnvFile;
@@ -21,6 +29,23 @@ use serde::{Deserialize, Serialize};
 const DISTRO_RUN_INFO_PATH: &str = "/var/run/distrod.json";
 const DISTRO_OLD_ROOT_PATH: &str = "/mnt/distrod_root";
 
+// Paths never meaningful to export: the kernel mounts these pseudo-filesystems
+// fresh on launch.
+const ROOTFS_EXPORT_EXCLUDES: &[&str] = &["proc", "sys", "dev", "run"];
+
+const DEFAULT_DISABLE_UNITS: &[&str] = &[
+    "dhcpcd.service",
+    "NetworkManager.service",
+    "multipathd.service",
+];
+const DEFAULT_MASK_UNITS: &[&str] = &["systemd-remount-fs.service", "systemd-modules-load.service"];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compression {
+    Gzip,
+    Zstd,
+}
+
 pub struct Distro {
     rootfs: PathBuf,
     container: Container,
@@ -32,6 +57,113 @@ pub struct DistroRunInfo {
     init_pid: u32,
 }
 
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+pub struct ResourceLimits {
+    pub memory_high: Option<u64>,
+    pub memory_max: Option<u64>,
+    pub cpu_weight: Option<u64>,
+    pub pids_max: Option<u64>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CommandOutcome {
+    Exited(i32),
+    Signaled(nix::sys::signal::Signal, bool),
+    Stopped(nix::sys::signal::Signal),
+}
+
+pub trait WaiterExt {
+    fn wait_outcome(&mut self) -> Result<CommandOutcome>;
+    fn check(&mut self) -> Result<()>;
+}
+
+impl WaiterExt for Waiter {
+    fn wait_outcome(&mut self) -> Result<CommandOutcome> {
+        match self
+            .wait()
+            .with_context(|| "Failed to wait for the command.")?
+        {
+            WaitStatus::Exited(_, code) => Ok(CommandOutcome::Exited(code)),
+            WaitStatus::Signaled(_, signal, core_dumped) => {
+                Ok(CommandOutcome::Signaled(signal, core_dumped))
+            }
+            WaitStatus::Stopped(_, signal) => Ok(CommandOutcome::Stopped(signal)),
+            other => bail!("Got an unexpected wait status: {:?}", other),
+        }
+    }
+
+    fn check(&mut self) -> Result<()> {
+        match self.wait_outcome()? {
+            CommandOutcome::Exited(0) => Ok(()),
+            CommandOutcome::Exited(code) => bail!("process exited with code {}", code),
+            CommandOutcome::Signaled(signal, core_dumped) => bail!(
+                "process killed by signal {}{}",
+                signal,
+                if core_dumped { " (core dumped)" } else { "" }
+            ),
+            CommandOutcome::Stopped(signal) => bail!("process stopped by signal {}", signal),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MountPoint {
+    pub source: PathBuf,
+    pub target: PathBuf,
+    #[serde(default)]
+    pub read_only: bool,
+    #[serde(default)]
+    pub create_target: bool,
+}
+
+// Rejects a ".." component, which would otherwise let a naive
+// rootfs.join(target) walk outside the rootfs the mount is confined to.
+fn validate_mount_target(target: &Path) -> Result<()> {
+    if target
+        .components()
+        .any(|component| component == std::path::Component::ParentDir)
+    {
+        bail!(
+            "mount target '{:?}' must not contain '..' components.",
+            target
+        );
+    }
+    Ok(())
+}
+
+fn apply_mounts_in_current_namespace(mounts: &[MountPoint]) -> Result<()> {
+    for mount in mounts {
+        if mount.create_target && !mount.target.exists() {
+            fs::create_dir_all(&mount.target)
+                .with_context(|| format!("Failed to create '{:?}'.", &mount.target))?;
+        }
+        nix::mount::mount(
+            Some(&mount.source),
+            &mount.target,
+            None::<&str>,
+            MsFlags::MS_BIND,
+            None::<&str>,
+        )
+        .with_context(|| {
+            format!(
+                "Failed to bind-mount '{:?}' onto '{:?}'.",
+                &mount.source, &mount.target
+            )
+        })?;
+        if mount.read_only {
+            nix::mount::mount(
+                None::<&str>,
+                &mount.target,
+                None::<&str>,
+                MsFlags::MS_BIND | MsFlags::MS_REMOUNT | MsFlags::MS_RDONLY,
+                None::<&str>,
+            )
+            .with_context(|| format!("Failed to remount '{:?}' read-only.", &mount.target))?;
+        }
+    }
+    Ok(())
+}
+
 impl Distro {
     pub fn get_installed_distro<P: AsRef<Path>>(rootfs: Option<P>) -> Result<Option<Distro>> {
         let create_container = |path: &Path| {
@@ -55,7 +187,7 @@ impl Distro {
     }
 
     pub fn get_running_distro() -> Result<Option<Distro>> {
-        let run_info_file = get_distro_run_info_file(false, false)
+        let run_info_file = get_distro_run_info_file()
             .with_context(|| "Failed to open the distro run info file.")?;
         if run_info_file.is_none() {
             return Ok(None);
@@ -89,6 +221,15 @@ impl Distro {
         self.container
             .launch(None, &self.rootfs, DISTRO_OLD_ROOT_PATH)
             .with_context(|| "Failed to launch a container.")?;
+        let mounts = self
+            .resolve_user_mounts()
+            .with_context(|| "Failed to resolve user-defined bind mounts.")?;
+        if let Err(e) = self.apply_user_mounts(&mounts) {
+            log::warn!("Failed to apply user-defined bind mounts. {:#?}", e);
+        }
+        if let Err(e) = self.apply_resource_limits() {
+            log::warn!("Failed to apply cgroup resource limits. {:#?}", e);
+        }
         self.export_run_info()?;
         Ok(())
     }
@@ -133,19 +274,141 @@ impl Distro {
         if let Err(e) = cleanup_etc_environment_file(&self.rootfs) {
             log::warn!("Failed to clean up /etc/environment. {:#?}", e);
         }
-        self.container.stop(sigkill)
+        let cgroup_dir = self.cgroup_dir();
+        self.container.stop(sigkill)?;
+        if let Ok(cgroup_dir) = cgroup_dir {
+            if cgroup_dir.exists() {
+                if let Err(e) = fs::remove_dir(&cgroup_dir) {
+                    log::warn!(
+                        "Failed to remove cgroup directory '{:?}'. {:?}",
+                        &cgroup_dir,
+                        e
+                    );
+                }
+            }
+        }
+        Ok(())
     }
 
-    fn export_run_info(&self) -> Result<()> {
-        if let Ok(Some(_)) = get_distro_run_info_file(false, false) {
-            fs::remove_file(&DISTRO_RUN_INFO_PATH)
-                .with_context(|| "Failed to remove the existing run info file.")?;
+    fn apply_resource_limits(&self) -> Result<()> {
+        let config =
+            DistrodConfig::get().with_context(|| "Failed to acquire the Distrod config.")?;
+        let limits = match config.resources {
+            Some(limits) => limits,
+            None => return Ok(()),
+        };
+        let init_pid = self.container.init_pid.ok_or_else(|| {
+            anyhow!("Distro is not launched yet, but resource limits are being applied.")
+        })?;
+        let cgroup_dir = self.cgroup_dir()?;
+        fs::create_dir_all(&cgroup_dir)
+            .with_context(|| format!("Failed to create cgroup directory '{:?}'.", &cgroup_dir))?;
+        let parent_dir = cgroup_dir
+            .parent()
+            .ok_or_else(|| anyhow!("'{:?}' has no parent cgroup.", &cgroup_dir))?;
+        fs::write(
+            parent_dir.join("cgroup.subtree_control"),
+            "+memory +cpu +pids",
+        )
+        .with_context(|| "Failed to enable cgroup controllers on the parent cgroup.")?;
+        if let Some(memory_high) = limits.memory_high {
+            fs::write(cgroup_dir.join("memory.high"), memory_high.to_string())
+                .with_context(|| "Failed to write memory.high.")?;
         }
-        let mut file = BufWriter::new(
-            get_distro_run_info_file(true, true)
-                .with_context(|| "Failed to create a run info file.")?
-                .expect("[BUG] get_distro_run_info_file shuold return Some when create:true"),
-        );
+        if let Some(memory_max) = limits.memory_max {
+            fs::write(cgroup_dir.join("memory.max"), memory_max.to_string())
+                .with_context(|| "Failed to write memory.max.")?;
+        }
+        if let Some(cpu_weight) = limits.cpu_weight {
+            fs::write(cgroup_dir.join("cpu.weight"), cpu_weight.to_string())
+                .with_context(|| "Failed to write cpu.weight.")?;
+        }
+        if let Some(pids_max) = limits.pids_max {
+            fs::write(cgroup_dir.join("pids.max"), pids_max.to_string())
+                .with_context(|| "Failed to write pids.max.")?;
+        }
+        fs::write(cgroup_dir.join("cgroup.procs"), init_pid.to_string()).with_context(|| {
+            format!("Failed to move PID {} into '{:?}'.", init_pid, &cgroup_dir)
+        })?;
+        Ok(())
+    }
+
+    fn cgroup_dir(&self) -> Result<PathBuf> {
+        let mounts = get_mount_entries().with_context(|| "Failed to read the mount table.")?;
+        let unified_root = mounts
+            .iter()
+            .find(|entry| entry.fstype == "cgroup2")
+            .map(|entry| entry.path.clone())
+            .ok_or_else(|| anyhow!("The unified cgroup v2 hierarchy is not mounted."))?;
+        let name = self.rootfs.file_name().ok_or_else(|| {
+            anyhow!(
+                "'{:?}' has no file name to derive a cgroup name from.",
+                &self.rootfs
+            )
+        })?;
+        Ok(unified_root.join("distrod").join(name))
+    }
+
+    fn resolve_user_mounts(&self) -> Result<Vec<MountPoint>> {
+        let config =
+            DistrodConfig::get().with_context(|| "Failed to acquire the Distrod config.")?;
+        for mount in &config.mounts {
+            validate_mount_target(&mount.target).with_context(|| {
+                format!("Rejecting configured mount for '{:?}'.", &mount.target)
+            })?;
+        }
+        Ok(config.mounts)
+    }
+
+    // Enters the container's own mount namespace before mounting, so the
+    // mounts are confined to the distro and torn down with its namespace.
+    fn apply_user_mounts(&self, mounts: &[MountPoint]) -> Result<()> {
+        if mounts.is_empty() {
+            return Ok(());
+        }
+        let init_pid = self
+            .container
+            .init_pid
+            .ok_or_else(|| anyhow!("Distro is not launched yet, but mounts are being applied."))?;
+        let own_ns = File::open("/proc/self/ns/mnt")
+            .with_context(|| "Failed to open this process's mount namespace.")?;
+        let container_ns_path = format!("/proc/{}/ns/mnt", init_pid);
+        let container_ns = File::open(&container_ns_path)
+            .with_context(|| format!("Failed to open '{}'.", &container_ns_path))?;
+        setns(container_ns.as_raw_fd(), CloneFlags::CLONE_NEWNS)
+            .with_context(|| "Failed to enter the container's mount namespace.")?;
+        let result = apply_mounts_in_current_namespace(mounts);
+        setns(own_ns.as_raw_fd(), CloneFlags::CLONE_NEWNS)
+            .with_context(|| "Failed to return to the original mount namespace.")?;
+        result
+    }
+
+    pub fn export_rootfs<W: Write>(&self, writer: W, compression: Compression) -> Result<()> {
+        match compression {
+            Compression::Gzip => {
+                let encoder = flate2::write::GzEncoder::new(writer, flate2::Compression::default());
+                let mut tar = tar::Builder::new(encoder);
+                append_rootfs_to_tar(&mut tar, &self.rootfs)?;
+                tar.into_inner()
+                    .with_context(|| "Failed to finish the tar stream.")?
+                    .finish()
+                    .with_context(|| "Failed to finish gzip compression.")?;
+            }
+            Compression::Zstd => {
+                let encoder = zstd::stream::write::Encoder::new(writer, 0)
+                    .with_context(|| "Failed to start zstd compression.")?;
+                let mut tar = tar::Builder::new(encoder);
+                append_rootfs_to_tar(&mut tar, &self.rootfs)?;
+                tar.into_inner()
+                    .with_context(|| "Failed to finish the tar stream.")?
+                    .finish()
+                    .with_context(|| "Failed to finish zstd compression.")?;
+            }
+        }
+        Ok(())
+    }
+
+    fn export_run_info(&self) -> Result<()> {
         let run_info = DistroRunInfo {
             rootfs: self.rootfs.clone(),
             init_pid: self
@@ -153,12 +416,113 @@ impl Distro {
                 .init_pid
                 .ok_or_else(|| anyhow!("Distro is not launched yet, but being exported."))?,
         };
-        file.write_all(&serde_json::to_vec(&run_info)?)
-            .with_context(|| "Failed to write to a distro run info file.")?;
+        let mut guard = AtomicWriteGuard::create(Path::new(DISTRO_RUN_INFO_PATH), 0o644)
+            .with_context(|| "Failed to create a temp file for the run info file.")?;
+        guard
+            .write_all(&serde_json::to_vec(&run_info)?)
+            .with_context(|| "Failed to write to the temp run info file.")?;
+        guard
+            .persist(Path::new(DISTRO_RUN_INFO_PATH))
+            .with_context(|| "Failed to persist the run info file.")?;
+        check_owned_by_root(Path::new(DISTRO_RUN_INFO_PATH))
+            .with_context(|| "The run info file is unsafe after being written.")?;
         Ok(())
     }
 }
 
+pub fn install_distro_from_tar<R: Read>(reader: R, dest_dir: &Path) -> Result<()> {
+    fs::create_dir_all(dest_dir).with_context(|| format!("Failed to create '{:?}'.", dest_dir))?;
+    let mut reader = BufReader::new(reader);
+    let magic = reader
+        .fill_buf()
+        .with_context(|| "Failed to read the archive header.")?;
+    if magic.starts_with(&[0x1f, 0x8b]) {
+        unpack_tar(flate2::read::GzDecoder::new(reader), dest_dir)?;
+    } else if magic.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+        let decoder = zstd::stream::read::Decoder::new(reader)
+            .with_context(|| "Failed to start zstd decompression.")?;
+        unpack_tar(decoder, dest_dir)?;
+    } else {
+        unpack_tar(reader, dest_dir)?;
+    }
+    initialize_distro_rootfs(dest_dir, true)
+        .with_context(|| "Failed to initialize the imported rootfs.")?;
+    Ok(())
+}
+
+fn unpack_tar<R: Read>(reader: R, dest_dir: &Path) -> Result<()> {
+    let mut archive = tar::Archive::new(reader);
+    archive.set_preserve_permissions(true);
+    archive.set_preserve_ownerships(true);
+    archive
+        .unpack(dest_dir)
+        .with_context(|| format!("Failed to unpack the archive into '{:?}'.", dest_dir))
+}
+
+fn append_rootfs_to_tar<W: Write>(tar: &mut tar::Builder<W>, rootfs: &Path) -> Result<()> {
+    let old_root_rel = Path::new(DISTRO_OLD_ROOT_PATH.trim_start_matches('/'));
+    let mut seen_inodes: HashMap<(u64, u64), PathBuf> = HashMap::new();
+    let mut dirs = vec![PathBuf::new()];
+    while let Some(rel_dir) = dirs.pop() {
+        let abs_dir = rootfs.join(&rel_dir);
+        let entries = fs::read_dir(&abs_dir)
+            .with_context(|| format!("Failed to read directory '{:?}'.", &abs_dir))?;
+        for entry in entries {
+            let entry = entry.with_context(|| format!("Failed to read '{:?}'.", &abs_dir))?;
+            let rel_path = rel_dir.join(entry.file_name());
+            if rel_path == old_root_rel
+                || ROOTFS_EXPORT_EXCLUDES
+                    .iter()
+                    .any(|excluded| rel_path == Path::new(excluded))
+            {
+                continue;
+            }
+            let abs_path = rootfs.join(&rel_path);
+            let metadata = fs::symlink_metadata(&abs_path)
+                .with_context(|| format!("Failed to stat '{:?}'.", &abs_path))?;
+            if metadata.is_dir() {
+                tar.append_dir(&rel_path, &abs_path)
+                    .with_context(|| format!("Failed to archive directory '{:?}'.", &rel_path))?;
+                dirs.push(rel_path);
+                continue;
+            }
+            if metadata.file_type().is_symlink() {
+                let target = fs::read_link(&abs_path)
+                    .with_context(|| format!("Failed to read symlink '{:?}'.", &abs_path))?;
+                let mut header = tar::Header::new_gnu();
+                header.set_metadata_in_mode(&metadata, tar::HeaderMode::Complete);
+                header.set_entry_type(tar::EntryType::Symlink);
+                header.set_size(0);
+                header.set_cksum();
+                tar.append_link(&mut header, &rel_path, &target)
+                    .with_context(|| format!("Failed to archive symlink '{:?}'.", &rel_path))?;
+                continue;
+            }
+            if metadata.st_nlink() > 1 {
+                let inode = (metadata.st_dev(), metadata.st_ino());
+                if let Some(first_path) = seen_inodes.get(&inode) {
+                    let mut header = tar::Header::new_gnu();
+                    header.set_metadata_in_mode(&metadata, tar::HeaderMode::Complete);
+                    header.set_entry_type(tar::EntryType::Link);
+                    header.set_size(0);
+                    header.set_cksum();
+                    tar.append_link(&mut header, &rel_path, first_path)
+                        .with_context(|| {
+                            format!("Failed to archive hard link '{:?}'.", &rel_path)
+                        })?;
+                    continue;
+                }
+                seen_inodes.insert(inode, rel_path.clone());
+            }
+            let mut file = File::open(&abs_path)
+                .with_context(|| format!("Failed to open '{:?}'.", &abs_path))?;
+            tar.append_file(&rel_path, &mut file)
+                .with_context(|| format!("Failed to archive file '{:?}'.", &rel_path))?;
+        }
+    }
+    Ok(())
+}
+
 pub fn initialize_distro_rootfs<P: AsRef<Path>>(
     path: P,
     overwrites_potential_userfiles: bool,
@@ -198,27 +562,39 @@ pub fn initialize_distro_rootfs<P: AsRef<Path>>(
             .with_context(|| format!("Failed to touch '{:?}'", &resolv_conf_path))?;
     }
 
-    // Disable or mask incompatible systemd services
-    let to_be_disabled = [
-        "dhcpcd.service",
-        "NetworkManager.service",
-        "multipathd.service",
-    ];
-    for unit in &to_be_disabled {
+    // Disable, mask or enable systemd services
+    let config = DistrodConfig::get().with_context(|| "Failed to acquire the Distrod config.")?;
+    let disable_units = merge_unit_lists(DEFAULT_DISABLE_UNITS, &config.disable_units);
+    for unit in &disable_units {
         if let Err(err) = SystemdUnitDisabler::new(path.as_ref(), unit).disable() {
             log::warn!("Faled to disable {}. Error: {:?}", unit, err);
         }
     }
-    let to_be_masked = ["systemd-remount-fs.service", "systemd-modules-load.service"];
-    for unit in &to_be_masked {
+    let mask_units = merge_unit_lists(DEFAULT_MASK_UNITS, &config.mask_units);
+    for unit in &mask_units {
         if let Err(err) = SystemdUnitDisabler::new(path.as_ref(), unit).mask() {
             log::warn!("Faled to mask {}. Error: {:?}", unit, err);
         }
     }
+    for unit in &config.enable_units {
+        if let Err(err) = SystemdUnitDisabler::new(path.as_ref(), unit).enable() {
+            log::warn!("Failed to enable {}. Error: {:?}", unit, err);
+        }
+    }
 
     Ok(())
 }
 
+fn merge_unit_lists(defaults: &[&str], configured: &[String]) -> Vec<String> {
+    let mut units: Vec<String> = defaults.iter().map(|s| s.to_string()).collect();
+    for unit in configured {
+        if !units.iter().any(|existing| existing == unit) {
+            units.push(unit.clone());
+        }
+    }
+    units
+}
+
 pub fn cleanup_distro_rootfs<P: AsRef<Path>>(path: P) -> Result<()> {
     let metadata = fs::metadata(path.as_ref())?;
     if !metadata.is_dir() {
@@ -313,27 +689,105 @@ fn remove_distrod_bin_from_path(path: &str) -> String {
     result.replace(&distrod_bin_path, "")
 }
 
-fn get_distro_run_info_file(create: bool, write: bool) -> Result<Option<File>> {
-    let mut json = fs::OpenOptions::new();
-    json.read(true);
-    if create {
-        json.create(true);
-    }
-    if write {
-        json.write(true);
-    }
-    let json = json.open(DISTRO_RUN_INFO_PATH);
+fn get_distro_run_info_file() -> Result<Option<File>> {
+    let json = fs::OpenOptions::new().read(true).open(DISTRO_RUN_INFO_PATH);
     if let Err(ref error) = json {
         if error.raw_os_error() == Some(nix::errno::Errno::ENOENT as i32) {
             return Ok(None);
         }
     }
     let json = json.with_context(|| "Failed to open the run info file of the distro.")?;
-    let metadata = json.metadata()?;
+    check_owned_by_root(Path::new(DISTRO_RUN_INFO_PATH))
+        .with_context(|| "The run info file of the distro is unsafe.")?;
+    Ok(Some(json))
+}
+
+fn check_owned_by_root<P: AsRef<Path>>(path: P) -> Result<()> {
+    let metadata = fs::metadata(path.as_ref())?;
     if metadata.st_uid() != 0 || metadata.st_gid() != 0 {
         bail!(
-            "The run info file of the distrod is unsafe, which is owned by a non-root user/group."
+            "'{:?}' is unsafe, since it is owned by a non-root user/group.",
+            path.as_ref()
         );
     }
-    Ok(Some(json))
+    Ok(())
+}
+
+// If the guard is dropped before `persist` succeeds, the temp file is
+// unlinked so a failed write never leaves debris behind. A process that is
+// killed mid-write does not run `Drop` and leaves the temp file in place.
+pub(crate) struct AtomicWriteGuard {
+    file: Option<File>,
+    tmp_path: PathBuf,
+    final_mode: u32,
+}
+
+impl AtomicWriteGuard {
+    pub(crate) fn create(final_path: &Path, final_mode: u32) -> Result<Self> {
+        let dir = final_path.parent().unwrap_or_else(|| Path::new("."));
+        let file_name = final_path
+            .file_name()
+            .ok_or_else(|| anyhow!("'{:?}' has no file name.", final_path))?
+            .to_string_lossy();
+        let mut rng = rand::thread_rng();
+        loop {
+            let tmp_path = dir.join(format!(".{}.tmp-{:016x}", file_name, rng.gen::<u64>()));
+            match OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .mode(0o600)
+                .open(&tmp_path)
+            {
+                Ok(file) => {
+                    return Ok(AtomicWriteGuard {
+                        file: Some(file),
+                        tmp_path,
+                        final_mode,
+                    })
+                }
+                Err(ref e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+                Err(e) => {
+                    return Err(e).with_context(|| format!("Failed to create '{:?}'.", &tmp_path))
+                }
+            }
+        }
+    }
+
+    pub(crate) fn write_all(&mut self, contents: &[u8]) -> Result<()> {
+        self.file
+            .as_mut()
+            .expect("[BUG] AtomicWriteGuard used after persist.")
+            .write_all(contents)
+            .with_context(|| format!("Failed to write to '{:?}'.", &self.tmp_path))
+    }
+
+    pub(crate) fn persist(mut self, final_path: &Path) -> Result<()> {
+        let file = self.file.as_ref().expect("[BUG] persist called twice.");
+        file.set_permissions(fs::Permissions::from_mode(self.final_mode))
+            .with_context(|| format!("Failed to chmod '{:?}'.", &self.tmp_path))?;
+        file.sync_all()
+            .with_context(|| format!("Failed to fsync '{:?}'.", &self.tmp_path))?;
+        fs::rename(&self.tmp_path, final_path).with_context(|| {
+            format!(
+                "Failed to rename '{:?}' to '{:?}'.",
+                &self.tmp_path, final_path
+            )
+        })?;
+        self.file = None;
+        Ok(())
+    }
+}
+
+impl Drop for AtomicWriteGuard {
+    fn drop(&mut self) {
+        if self.file.is_none() {
+            // persist() already renamed the temp file away.
+            return;
+        }
+        if let Err(e) = fs::remove_file(&self.tmp_path) {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                log::warn!("Failed to remove temp file '{:?}'. {:?}", &self.tmp_path, e);
+            }
+        }
+    }
 }
@@ -0,0 +1,45 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+
+use crate::distro::{MountPoint, ResourceLimits};
+
+const DISTROD_CONFIG_PATH: &str = "/etc/distrod.toml";
+const DISTROD_BIN_DIR_PATH: &str = "/opt/distrod/bin";
+
+#[derive(Serialize, Deserialize)]
+pub struct DistrodConfig {
+    pub distrod: DistrodMainConfig,
+
+    #[serde(default)]
+    pub resources: Option<ResourceLimits>,
+
+    #[serde(default)]
+    pub disable_units: Vec<String>,
+    #[serde(default)]
+    pub mask_units: Vec<String>,
+    #[serde(default)]
+    pub enable_units: Vec<String>,
+
+    #[serde(default, rename = "mount")]
+    pub mounts: Vec<MountPoint>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct DistrodMainConfig {
+    pub default_distro_image: PathBuf,
+}
+
+impl DistrodConfig {
+    pub fn get() -> Result<Self> {
+        let content = fs::read_to_string(DISTROD_CONFIG_PATH)
+            .with_context(|| format!("Failed to read '{}'.", DISTROD_CONFIG_PATH))?;
+        toml::from_str(&content)
+            .with_context(|| format!("Failed to parse '{}'.", DISTROD_CONFIG_PATH))
+    }
+}
+
+pub fn get_distrod_bin_dir_path() -> &'static str {
+    DISTROD_BIN_DIR_PATH
+}
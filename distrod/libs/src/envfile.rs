@@ -0,0 +1,74 @@
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::distro::AtomicWriteGuard;
+
+pub struct EnvFile {
+    path: PathBuf,
+    vars: Vec<(String, String)>,
+}
+
+impl EnvFile {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let vars = if path.exists() {
+            let content = fs::read_to_string(&path)
+                .with_context(|| format!("Failed to read '{:?}'.", &path))?;
+            parse(&content)
+        } else {
+            Vec::new()
+        };
+        Ok(EnvFile { path, vars })
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.vars
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn put(&mut self, key: &str, value: String) {
+        match self.vars.iter_mut().find(|(k, _)| k == key) {
+            Some(entry) => entry.1 = value,
+            None => self.vars.push((key.to_string(), value)),
+        }
+    }
+
+    pub fn remove(&mut self, key: &str) {
+        self.vars.retain(|(k, _)| k != key);
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let mut contents = String::new();
+        for (key, value) in &self.vars {
+            contents.push_str(key);
+            contents.push('=');
+            contents.push_str(value);
+            contents.push('\n');
+        }
+        let mut guard = AtomicWriteGuard::create(&self.path, 0o644)
+            .with_context(|| format!("Failed to create a temp file for '{:?}'.", &self.path))?;
+        guard
+            .write_all(contents.as_bytes())
+            .with_context(|| format!("Failed to write to the temp file for '{:?}'.", &self.path))?;
+        guard
+            .persist(&self.path)
+            .with_context(|| format!("Failed to persist '{:?}'.", &self.path))
+    }
+}
+
+fn parse(content: &str) -> Vec<(String, String)> {
+    content
+        .lines()
+        .filter_map(|line| {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                return None;
+            }
+            line.split_once('=')
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+        })
+        .collect()
+}
@@ -0,0 +1,93 @@
+use anyhow::{anyhow, Context, Result};
+use std::fs;
+use std::os::unix::fs::symlink;
+use std::path::{Path, PathBuf};
+
+// Directories systemd searches for unit files, in priority order.
+const UNIT_SEARCH_DIRS: &[&str] = &[
+    "etc/systemd/system",
+    "usr/lib/systemd/system",
+    "lib/systemd/system",
+    "usr/local/lib/systemd/system",
+];
+
+// "..", one per component of "etc/systemd/system/multi-user.target.wants".
+const WANTS_DIR_DEPTH: usize = 4;
+
+pub struct SystemdUnitDisabler {
+    rootfs: PathBuf,
+    unit: String,
+}
+
+impl SystemdUnitDisabler {
+    pub fn new<P: AsRef<Path>>(rootfs: P, unit: &str) -> Self {
+        SystemdUnitDisabler {
+            rootfs: rootfs.as_ref().to_path_buf(),
+            unit: unit.to_string(),
+        }
+    }
+
+    pub fn disable(&self) -> Result<()> {
+        let link = self.wants_link_path();
+        if link.symlink_metadata().is_err() {
+            return Ok(());
+        }
+        fs::remove_file(&link).with_context(|| format!("Failed to remove '{:?}'.", &link))
+    }
+
+    pub fn mask(&self) -> Result<()> {
+        let unit_path = self.unit_file_path();
+        if let Some(parent) = unit_path.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create '{:?}'.", parent))?;
+        }
+        if unit_path.symlink_metadata().is_ok() {
+            fs::remove_file(&unit_path)
+                .with_context(|| format!("Failed to remove '{:?}'.", &unit_path))?;
+        }
+        symlink("/dev/null", &unit_path)
+            .with_context(|| format!("Failed to mask '{:?}'.", &unit_path))
+    }
+
+    pub fn enable(&self) -> Result<()> {
+        let link = self.wants_link_path();
+        if link.symlink_metadata().is_ok() {
+            return Ok(());
+        }
+        let unit_dir = self.find_unit_dir().ok_or_else(|| {
+            anyhow!(
+                "Could not find '{}' under any systemd unit directory in '{:?}'.",
+                &self.unit,
+                &self.rootfs
+            )
+        })?;
+        if let Some(parent) = link.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create '{:?}'.", parent))?;
+        }
+        let mut target = PathBuf::new();
+        for _ in 0..WANTS_DIR_DEPTH {
+            target.push("..");
+        }
+        target.push(unit_dir);
+        target.push(&self.unit);
+        symlink(&target, &link).with_context(|| format!("Failed to enable '{:?}'.", &link))
+    }
+
+    fn find_unit_dir(&self) -> Option<&'static str> {
+        UNIT_SEARCH_DIRS
+            .iter()
+            .copied()
+            .find(|dir| self.rootfs.join(dir).join(&self.unit).is_file())
+    }
+
+    fn unit_file_path(&self) -> PathBuf {
+        self.rootfs.join("etc/systemd/system").join(&self.unit)
+    }
+
+    fn wants_link_path(&self) -> PathBuf {
+        self.rootfs
+            .join("etc/systemd/system/multi-user.target.wants")
+            .join(&self.unit)
+    }
+}